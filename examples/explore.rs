@@ -0,0 +1,35 @@
+use hiatus::explore::{spawn, yield_point};
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+// Two threads do a non-atomic read-modify-write of a shared counter. With the right interleaving
+// one of the increments is lost, so the final value is 1 instead of 2. We let Hiatus discover that
+// interleaving for us rather than writing it down by hand.
+fn main() {
+    let schedule = hiatus::explore(|| {
+        let counter = Arc::new(Mutex::new(0u64));
+
+        let c1 = counter.clone();
+        let c2 = counter.clone();
+
+        let t1 = spawn(move || {
+            let value = *c1.lock();
+            yield_point();
+            *c1.lock() = value + 1;
+        });
+        let t2 = spawn(move || {
+            let value = *c2.lock();
+            yield_point();
+            *c2.lock() = value + 1;
+        });
+        t1.join().unwrap();
+        t2.join().unwrap();
+
+        assert_eq!(*counter.lock(), 2, "lost an increment");
+    });
+
+    match schedule {
+        Some(threads) => println!("found a failing schedule: {:?}", threads),
+        None => println!("no failing interleaving found"),
+    }
+}
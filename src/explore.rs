@@ -0,0 +1,269 @@
+//! Systematic exploration of thread interleavings.
+//!
+//! Where [`step`](../fn.step.html) makes you write down *one* ordering by hand, [`explore`]
+//! searches for a buggy ordering on your behalf. You give it a closure that spawns some worker
+//! threads (with [`spawn`]) and sprinkles [`yield_point`] calls at the points where a preemption
+//! could matter. Hiatus then runs the closure over and over, and on each run a central scheduler
+//! lets exactly one thread make progress between yield points. The set of scheduling decisions
+//! forms a tree, and the scheduler walks that tree depth-first until it either exhausts every
+//! interleaving or finds one that panics.
+//!
+//! When a run fails, `explore` returns the sequence of thread-ids that produced the failure, which
+//! is a minimal reproducer you can read off directly.
+//!
+//! This module is **experimental**, just like the rest of the crate.
+use lazy_static::lazy_static;
+use parking_lot::{Condvar, Mutex};
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle, ThreadId};
+
+lazy_static! {
+    static ref SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+    /// Signalled whenever a thread gives up the run token, so the driver can pick the next one.
+    static ref DRIVER: Condvar = Condvar::new();
+    static ref FAILED: AtomicBool = AtomicBool::new(false);
+    static ref NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Shared scheduling state for a single call to [`explore`].
+///
+/// Only one worker thread holds the run token (`running`) at a time; every other participating
+/// thread is either parked at a yield point (in `runnable`) or blocked in the user's code.
+struct Scheduler {
+    /// Threads parked at a yield point, waiting to be handed the run token.
+    runnable: Vec<ThreadId>,
+    /// The thread currently allowed to run, if any.
+    running: Option<ThreadId>,
+    /// Per-thread condition variables, so the driver can wake exactly one parked thread.
+    parked: HashMap<ThreadId, Arc<Condvar>>,
+    /// Stable ids assigned to threads in spawn order, used for replay and reporting.
+    ids: HashMap<ThreadId, usize>,
+    /// Worker threads started but not yet finished on this run.
+    live: usize,
+    /// Set once the exploration closure has fully returned (all workers joined).
+    finished: bool,
+    /// The schedule being replayed: the choice index to take at each decision point.
+    replay: Vec<usize>,
+    /// The choices actually taken this run (the replayed prefix, then leftmost choices).
+    choices: Vec<usize>,
+    /// How many threads were runnable at each decision this run.
+    branches: Vec<usize>,
+    /// The stable thread-ids in the order they were scheduled this run.
+    order: Vec<usize>,
+}
+
+impl Scheduler {
+    fn new() -> Self {
+        Scheduler {
+            runnable: Vec::new(),
+            running: None,
+            parked: HashMap::new(),
+            ids: HashMap::new(),
+            live: 0,
+            finished: false,
+            replay: Vec::new(),
+            choices: Vec::new(),
+            branches: Vec::new(),
+            order: Vec::new(),
+        }
+    }
+}
+
+/// Spawn a worker thread that participates in the current [`explore`] run.
+///
+/// Use this in place of [`std::thread::spawn`] inside an `explore` closure. The thread registers
+/// itself with the scheduler before running `f`, so that its [`yield_point`] calls take part in
+/// the interleaving search, and it deregisters itself once `f` returns (or panics). You still own
+/// the returned [`JoinHandle`] and **must** join it before the closure returns.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    // Count this worker as live synchronously, before its OS thread has had a chance to run, so
+    // that the driver's quiescence check (`runnable.len() == live`) cannot fire a decision until
+    // every spawned worker has registered. Otherwise whether the child has registered yet would be
+    // an OS-scheduler race, and the recorded branch counts would be nondeterministic.
+    SCHEDULER.lock().live += 1;
+    thread::spawn(move || {
+        register(id);
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        finish(result.is_err());
+        match result {
+            Ok(value) => value,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    })
+}
+
+/// A preemption point for use inside an [`explore`] closure.
+///
+/// The calling thread becomes runnable and gives up the run token, then blocks on its own
+/// condition variable until the scheduler hands the token back to it. Exactly one thread runs
+/// between any two yield points. Calling `yield_point` outside of `explore` is a no-op.
+pub fn yield_point() {
+    let me = thread::current().id();
+    let mut sched = SCHEDULER.lock();
+    if !sched.ids.contains_key(&me) {
+        // Not running under `explore`; nothing to coordinate.
+        return;
+    }
+    sched.runnable.push(me);
+    sched.running = None;
+    DRIVER.notify_one();
+    wait_for_turn(sched, me);
+}
+
+/// Explore the interleavings of the worker threads spawned by `closure`.
+///
+/// `closure` is run repeatedly. Each run follows a different path through the tree of scheduling
+/// decisions, visited depth-first. Exploration stops and returns `Some(schedule)` as soon as a run
+/// panics (for example, from a failed `assert!`), where `schedule` is the sequence of thread-ids
+/// that were scheduled, in order. If every interleaving completes without panicking, `explore`
+/// returns `None`.
+///
+/// The closure must spawn its workers with [`spawn`] and join every one of them before returning.
+pub fn explore<F>(closure: F) -> Option<Vec<usize>>
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let closure = Arc::new(closure);
+    let mut replay: Vec<usize> = Vec::new();
+    loop {
+        reset(replay.clone());
+
+        // Run the closure on its own thread so that the current thread is free to act as the
+        // scheduler driver while the closure blocks in `join`.
+        let body = closure.clone();
+        let harness = thread::spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| (body)()));
+            let mut sched = SCHEDULER.lock();
+            sched.finished = true;
+            if result.is_err() {
+                FAILED.store(true, Ordering::SeqCst);
+            }
+            DRIVER.notify_one();
+        });
+        drive();
+        let _ = harness.join();
+
+        let (choices, branches, order) = {
+            let sched = SCHEDULER.lock();
+            (sched.choices.clone(), sched.branches.clone(), sched.order.clone())
+        };
+
+        if FAILED.load(Ordering::SeqCst) {
+            return Some(order);
+        }
+
+        match next_replay(&choices, &branches) {
+            Some(next) => replay = next,
+            // Every leaf of the choice tree has been visited.
+            None => return None,
+        }
+    }
+}
+
+/// Register the calling thread with the scheduler and park it until it is first scheduled.
+fn register(id: usize) {
+    let me = thread::current().id();
+    let mut sched = SCHEDULER.lock();
+    sched.ids.insert(me, id);
+    sched.parked.insert(me, Arc::new(Condvar::new()));
+    // `live` was already incremented in `spawn`; here we only become runnable.
+    sched.runnable.push(me);
+    DRIVER.notify_one();
+    wait_for_turn(sched, me);
+}
+
+/// Give up the run token for good once a worker has finished.
+fn finish(failed: bool) {
+    let me = thread::current().id();
+    let mut sched = SCHEDULER.lock();
+    sched.running = None;
+    sched.live -= 1;
+    sched.parked.remove(&me);
+    if failed {
+        FAILED.store(true, Ordering::SeqCst);
+    }
+    DRIVER.notify_one();
+}
+
+/// Block on the calling thread's condition variable until it holds the run token.
+fn wait_for_turn(mut sched: parking_lot::MutexGuard<'_, Scheduler>, me: ThreadId) {
+    let cv = sched.parked[&me].clone();
+    while sched.running != Some(me) {
+        cv.wait(&mut sched);
+    }
+}
+
+/// Drive scheduling decisions until the closure has returned and every worker has finished.
+fn drive() {
+    let mut sched = SCHEDULER.lock();
+    loop {
+        if sched.finished && sched.live == 0 && sched.runnable.is_empty() && sched.running.is_none()
+        {
+            return;
+        }
+        // Only decide once the runnable set is quiescent: every live worker has reached a yield or
+        // registration point. This makes the branch count at each decision depend solely on the
+        // interleaving, not on OS-scheduler timing, so replaying a prefix reproduces the same
+        // choice tree and the DFS is complete and terminating.
+        if sched.running.is_some() || sched.runnable.is_empty() || sched.runnable.len() != sched.live
+        {
+            DRIVER.wait(&mut sched);
+            continue;
+        }
+
+        // A decision point: choose one of the runnable threads to wake. Sort by stable id so the
+        // same choice index always refers to the same thread across runs. Snapshot the id map
+        // first so the sort closure doesn't borrow `sched` while `runnable` is borrowed mutably.
+        let ids = sched.ids.clone();
+        sched.runnable.sort_by_key(|tid| ids[tid]);
+        let options = sched.runnable.len();
+        let k = sched.choices.len();
+        let idx = if k < sched.replay.len() {
+            // Quiescence guarantees the branch count is stable across runs, so a replayed choice
+            // index is always in range.
+            assert!(sched.replay[k] < options, "replayed choice out of range");
+            sched.replay[k]
+        } else {
+            0
+        };
+        sched.choices.push(idx);
+        sched.branches.push(options);
+
+        let chosen = sched.runnable.remove(idx);
+        let sid = sched.ids[&chosen];
+        sched.order.push(sid);
+        sched.running = Some(chosen);
+        let cv = sched.parked[&chosen].clone();
+        cv.notify_one();
+    }
+}
+
+/// Reset the global scheduler state for a fresh run replaying `replay`.
+fn reset(replay: Vec<usize>) {
+    NEXT_ID.store(0, Ordering::SeqCst);
+    FAILED.store(false, Ordering::SeqCst);
+    let mut sched = SCHEDULER.lock();
+    *sched = Scheduler::new();
+    sched.replay = replay;
+}
+
+/// Compute the next schedule to replay by advancing the deepest choice that has an unexplored
+/// sibling, i.e. one step of the depth-first walk. Returns `None` once the tree is exhausted.
+fn next_replay(choices: &[usize], branches: &[usize]) -> Option<Vec<usize>> {
+    for i in (0..choices.len()).rev() {
+        if choices[i] + 1 < branches[i] {
+            let mut next = choices[..i].to_vec();
+            next.push(choices[i] + 1);
+            return Some(next);
+        }
+    }
+    None
+}
@@ -4,16 +4,46 @@
 //! confirm the existence of the bug.
 //!
 //! This library is **experimental**!
+//!
+//! As well as confirming a hand-written ordering with [`step`], Hiatus can *discover* a buggy
+//! ordering for you: see the [`explore`](./explore/index.html) module.
 use lazy_static::lazy_static;
 use parking_lot::{Condvar, Mutex, MutexGuard};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+pub mod explore;
+
+pub use explore::{explore, yield_point};
 
 lazy_static! {
     static ref CURRENT_STEP: Mutex<u64> = Mutex::new(1);
-    static ref CONDVAR: Condvar = Condvar::new();
     static ref ENABLED: AtomicBool = AtomicBool::new(false);
+    /// Maps a step number to the threads parked waiting for it, so that completing a step can
+    /// unpark exactly those threads instead of waking every waiter. It's almost always a single
+    /// thread per step, but we keep a `Vec` so that the "multiple calls for the same step" edge
+    /// case still wakes every waiter rather than only the last registrant.
+    static ref PARKED: Mutex<HashMap<u64, Vec<Thread>>> = Mutex::new(HashMap::new());
+    static ref STEP_FLAGS: Mutex<HashMap<&'static str, bool>> = Mutex::new(HashMap::new());
+    static ref NAMED_CONDVAR: Condvar = Condvar::new();
+    /// Maps a `step_async` future's id to the step it awaits and its latest `Waker`. Keying by
+    /// future id means re-polling replaces the waker instead of appending a duplicate, and a
+    /// future's `Drop` can remove its own entry so an abandoned future doesn't leak.
+    static ref ASYNC_WAKERS: Mutex<HashMap<usize, (u64, Waker)>> = Mutex::new(HashMap::new());
+    static ref NEXT_WAKER_ID: AtomicUsize = AtomicUsize::new(0);
+    static ref TIMEOUT: Mutex<Duration> = Mutex::new(DEFAULT_TIMEOUT);
+    static ref WAITING: Mutex<BTreeSet<u64>> = Mutex::new(BTreeSet::new());
+    static ref ABORT: AtomicBool = AtomicBool::new(false);
 }
 
+/// Default per-step timeout used until overridden with [`set_timeout`].
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
 /// Breakpoint object returned by `step`, with drop semantics.
 ///
 /// See the docs for [`step`](./fn.step.html) for usage.
@@ -24,6 +54,11 @@ pub enum Step<'a> {
         n: u64,
         current_step: MutexGuard<'a, u64>,
     },
+    /// Step variant used by [`step_async`](./fn.step_async.html).
+    ///
+    /// Unlike [`Step::Real`], this variant does not hold the step-count mutex between yield and
+    /// drop, so it can be held across an `await`. Its `Drop` re-locks the counter to advance it.
+    Async { n: u64 },
     /// Step variant used when Hiatus is disabled.
     Dummy,
 }
@@ -80,35 +115,406 @@ pub fn step<'a>(n: u64) -> Step<'a> {
 }
 
 fn real_step<'a>(n: u64) -> Step<'a> {
-    // Use the condition variable to wait for the step count to reach `n`.
-    let mut current_step = CURRENT_STEP.lock();
-    while *current_step != n {
-        CONDVAR.wait(&mut current_step);
+    let deadline = Instant::now() + *TIMEOUT.lock();
+    // Record that we're waiting for `n`, so a timeout report can list every awaited step, and
+    // register this thread as a waiter for `n` so the completing step can unpark it directly.
+    WAITING.lock().insert(n);
+    PARKED.lock().entry(n).or_default().push(thread::current());
+    // Park the thread until the step count reaches `n`, giving up after the configured timeout so
+    // an impossible ordering fails loudly instead of hanging forever.
+    loop {
+        let current = *CURRENT_STEP.lock();
+        if current == n {
+            break;
+        }
+        if ABORT.load(Ordering::SeqCst) {
+            let report = abort_report(n, current);
+            deregister(n);
+            panic!("{}", report);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            let report = timeout_report(n, current);
+            // Wake everyone else so the whole run unwinds rather than one thread at a time.
+            ABORT.store(true, Ordering::SeqCst);
+            NAMED_CONDVAR.notify_all();
+            unpark_all();
+            // Drop our own bookkeeping before unwinding so it doesn't leak past the panic.
+            deregister(n);
+            panic!("{}", report);
+        }
+        thread::park_timeout(deadline - now);
     }
-    // Step count has reached `n`, and we hold the mutex.
-    // Return the value and let the caller execute their critical section.
+    deregister(n);
+    // Step count has reached `n`. Take the mutex and let the caller execute their critical section.
     // When they're done, they should drop the `Step` to indicate that the next step is
     // allowed to run.
+    let current_step = CURRENT_STEP.lock();
     Step::Real { n, current_step }
 }
 
+/// Set the timeout applied to each [`step`] wait. A step that waits longer than this aborts the
+/// run (see [`abort`]) instead of blocking forever.
+pub fn set_timeout(timeout: Duration) {
+    *TIMEOUT.lock() = timeout;
+}
+
+/// Abort the run: wake every blocked [`step`] and make it panic with a diagnostic report.
+///
+/// This is intended for an external watchdog that has decided the run is stuck.
+pub fn abort() {
+    ABORT.store(true, Ordering::SeqCst);
+    NAMED_CONDVAR.notify_all();
+    unpark_all();
+}
+
+/// Reset Hiatus to its initial state so a fresh run can start after a timeout or [`abort`].
+///
+/// This clears the latched abort flag, rewinds the step counter, and discards any stale waiter
+/// bookkeeping. Without it a single timeout or watchdog `abort` would make every later [`step`]
+/// panic for the rest of the process, so call `reset` between independent runs.
+pub fn reset() {
+    ABORT.store(false, Ordering::SeqCst);
+    *CURRENT_STEP.lock() = 1;
+    PARKED.lock().clear();
+    WAITING.lock().clear();
+    STEP_FLAGS.lock().clear();
+    ASYNC_WAKERS.lock().clear();
+}
+
+/// Advance the step count to the newly reached value `count`, unparking exactly the threads that
+/// registered for it (usually one). If none is registered we fall back to unparking every waiter
+/// so the run can still make progress.
+fn advance_to(count: u64) {
+    let waiters = PARKED.lock().remove(&count).unwrap_or_default();
+    if waiters.is_empty() {
+        unpark_all();
+    } else {
+        for thread in waiters {
+            thread.unpark();
+        }
+    }
+    wake_async(count);
+}
+
+/// Remove the calling thread's registration for step `n` from both `PARKED` and `WAITING`.
+fn deregister(n: u64) {
+    let me = thread::current().id();
+    let mut parked = PARKED.lock();
+    if let Some(threads) = parked.get_mut(&n) {
+        threads.retain(|thread| thread.id() != me);
+        if threads.is_empty() {
+            parked.remove(&n);
+        }
+    }
+    drop(parked);
+    WAITING.lock().remove(&n);
+}
+
+/// Unpark every thread currently parked on a step (the broadcast fallback).
+fn unpark_all() {
+    for threads in PARKED.lock().values() {
+        for thread in threads {
+            thread.unpark();
+        }
+    }
+}
+
+/// Build the report for a step that timed out waiting for the counter to reach `n`.
+fn timeout_report(n: u64, reached: u64) -> String {
+    format!(
+        "Hiatus timed out: stuck waiting for step {}, reached step {}; steps still awaited: {:?}",
+        n,
+        reached,
+        awaited_steps(),
+    )
+}
+
+/// Build the report for a step woken by an abort triggered elsewhere.
+fn abort_report(n: u64, reached: u64) -> String {
+    format!(
+        "Hiatus aborted: was waiting for step {}, reached step {}; steps still awaited: {:?}",
+        n,
+        reached,
+        awaited_steps(),
+    )
+}
+
+/// Snapshot of the step numbers currently being awaited, in ascending order.
+fn awaited_steps() -> Vec<u64> {
+    WAITING.lock().iter().copied().collect()
+}
+
+/// Set a breakpoint in asynchronous code.
+///
+/// This is the `async` counterpart of [`step`](./fn.step.html). Rather than parking the OS thread
+/// — which would deadlock when several tasks share one executor thread —
+/// the returned future registers its [`Waker`] against step `n` and yields `Poll::Pending` until
+/// the global step count reaches `n`. When a [`Step`] is dropped and the counter is incremented,
+/// every waker registered for the new count is woken.
+///
+/// The awaited value is an ordinary [`Step`] with the same `Drop` and
+/// [`then`](./enum.Step.html#method.then) contract as [`step`]:
+///
+/// ```no_run
+/// # async fn example() {
+/// let s = hiatus::step_async(1).await;
+/// // ... do some work ...
+/// drop(s);
+/// # }
+/// ```
+pub fn step_async(n: u64) -> impl Future<Output = Step<'static>> {
+    assert_ne!(n, 0, "steps start from 1");
+    StepFuture {
+        n,
+        id: NEXT_WAKER_ID.fetch_add(1, Ordering::SeqCst),
+        deadline: Instant::now() + *TIMEOUT.lock(),
+    }
+}
+
+/// Future returned by [`step_async`].
+struct StepFuture {
+    n: u64,
+    /// Unique id so re-polling replaces this future's waker rather than appending a new one.
+    id: usize,
+    deadline: Instant,
+}
+
+impl Future for StepFuture {
+    type Output = Step<'static>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if !is_enabled() {
+            return Poll::Ready(Step::Dummy);
+        }
+        // Hold the step-count lock while deciding whether to register, so that a concurrent
+        // `Drop` cannot advance the counter between our check and our registration and lose the
+        // wakeup. This matches the lock ordering used in `Drop` (count first, then wakers).
+        let current_step = CURRENT_STEP.lock();
+        if *current_step == self.n {
+            ASYNC_WAKERS.lock().remove(&self.id);
+            return Poll::Ready(Step::Async { n: self.n });
+        }
+        // Fail loudly if the step can no longer be reached: it has already been passed, the run was
+        // aborted, or the deadline has expired. These checks only run when the future is polled;
+        // no timer wakes a pending future at its deadline, and an abort does not wake async tasks.
+        if *current_step > self.n {
+            let report = async_report("overshot", self.n, *current_step);
+            drop(current_step);
+            ASYNC_WAKERS.lock().remove(&self.id);
+            panic!("{}", report);
+        }
+        if ABORT.load(Ordering::SeqCst) {
+            let report = async_report("aborted", self.n, *current_step);
+            drop(current_step);
+            ASYNC_WAKERS.lock().remove(&self.id);
+            panic!("{}", report);
+        }
+        if Instant::now() >= self.deadline {
+            let report = async_report("timed out", self.n, *current_step);
+            ABORT.store(true, Ordering::SeqCst);
+            drop(current_step);
+            unpark_all();
+            ASYNC_WAKERS.lock().remove(&self.id);
+            panic!("{}", report);
+        }
+        // Record (or refresh) this future's waker against its target step.
+        ASYNC_WAKERS
+            .lock()
+            .insert(self.id, (self.n, cx.waker().clone()));
+        Poll::Pending
+    }
+}
+
+impl Drop for StepFuture {
+    /// Drop this future's waker registration so an abandoned future doesn't leak.
+    fn drop(&mut self) {
+        ASYNC_WAKERS.lock().remove(&self.id);
+    }
+}
+
+/// Build a diagnostic for an async step that cannot make progress.
+fn async_report(what: &str, n: u64, current: u64) -> String {
+    format!(
+        "Hiatus {}: async step {} cannot be reached, step count is at {}",
+        what, n, current,
+    )
+}
+
+/// Wake and drop every async waker registered for step `count`.
+fn wake_async(count: u64) {
+    let mut wakers = ASYNC_WAKERS.lock();
+    let ids: Vec<usize> = wakers
+        .iter()
+        .filter(|(_, (target, _))| *target == count)
+        .map(|(id, _)| *id)
+        .collect();
+    let mut ready = Vec::new();
+    for id in ids {
+        if let Some((_, waker)) = wakers.remove(&id) {
+            ready.push(waker);
+        }
+    }
+    drop(wakers);
+    for waker in ready {
+        waker.wake();
+    }
+}
+
 impl<'a> Step<'a> {
     /// Shorthand for dropping this step and moving to a new step `n`.
+    ///
+    /// This blocks the current thread in [`step`], so it must **not** be called on a
+    /// [`Step::Async`] — doing so would park the executor thread, the very deadlock
+    /// [`step_async`] exists to avoid. Use [`then_async`](#method.then_async) to chain async steps.
     pub fn then(self, n: u64) -> Step<'a> {
+        debug_assert!(
+            !matches!(self, Step::Async { .. }),
+            "use `then_async` to chain a step created with `step_async`",
+        );
         drop(self);
         step(n)
     }
+
+    /// Asynchronous counterpart of [`then`](#method.then): drop this step and `await` the next one.
+    ///
+    /// Unlike [`then`](#method.then) this never parks the OS thread, so it is safe to use when
+    /// several async tasks share one executor thread.
+    pub fn then_async(self, n: u64) -> impl Future<Output = Step<'static>> {
+        drop(self);
+        step_async(n)
+    }
 }
 
 impl<'a> Drop for Step<'a> {
     /// Increment the global step count, and signal the condition variable to wake up waiters.
     fn drop(&mut self) {
-        if let Step::Real { current_step, .. } = self {
-            // Increment the step count.
-            **current_step += 1;
-            // Signal all the other waiters (a little inefficient -- but the alternative is one
-            // condition variable per step, which seems unwieldy).
-            CONDVAR.notify_all();
+        match self {
+            Step::Real { current_step, .. } => {
+                // Increment the step count, then unpark exactly the threads waiting for the new
+                // step (falling back to a broadcast if none is registered).
+                **current_step += 1;
+                let count = **current_step;
+                advance_to(count);
+            }
+            Step::Async { .. } => {
+                // No guard is held, so re-lock the counter to advance it and wake the waiters
+                // (both parked threads and async tasks) for the new step.
+                let count = {
+                    let mut current_step = CURRENT_STEP.lock();
+                    *current_step += 1;
+                    *current_step
+                };
+                advance_to(count);
+            }
+            Step::Dummy => {}
+        }
+    }
+}
+
+/// Breakpoint object returned by [`step_named`], with drop semantics.
+///
+/// See the docs for [`step_named`](./fn.step_named.html) for usage.
+#[must_use]
+pub enum NamedStep {
+    /// Named step variant used when Hiatus is enabled.
+    Real { name: &'static str },
+    /// Named step variant used when Hiatus is disabled.
+    Dummy,
+}
+
+/// Set a *named* breakpoint with an explicit set of predecessors.
+///
+/// Unlike [`step`](./fn.step.html), which forces a single total order through a global counter,
+/// named steps let you describe a happens-before DAG directly: a step declares which other steps
+/// must complete before it may run, and steps with no ordering relation between them are free to
+/// run concurrently.
+///
+/// `step_named(name)` registers the step and, on its own, imposes no ordering. Declare
+/// predecessors with [`NamedStep::after`]:
+///
+/// ```no_run
+/// // `c` runs only after both `a` and `b` have completed.
+/// let c = hiatus::step_named("c").after(&["a", "b"]);
+/// ```
+///
+/// As with [`step`], completion is signalled by dropping the returned value, so you can hold it
+/// until the block of code it guards has finished. Make sure you enable Hiatus by calling
+/// [`enable`](./fn.enable.html) first.
+pub fn step_named(name: &'static str) -> NamedStep {
+    if is_enabled() {
+        // Record the step as not-yet-complete so that anything waiting on it blocks correctly.
+        STEP_FLAGS.lock().entry(name).or_insert(false);
+        NamedStep::Real { name }
+    } else {
+        NamedStep::Dummy
+    }
+}
+
+impl NamedStep {
+    /// Block until every step in `predecessors` has completed.
+    ///
+    /// This waits in a loop on the shared condition variable until each named predecessor's
+    /// completion flag is set, then returns `self` so the call reads as a single expression.
+    ///
+    /// Like [`step`](./fn.step.html), the wait is bounded by the configured timeout (see
+    /// [`set_timeout`]) and respects [`abort`], so an impossible ordering — for example a
+    /// predecessor name that is never registered — fails loudly with the list of outstanding
+    /// predecessors instead of hanging forever.
+    pub fn after(self, predecessors: &[&'static str]) -> NamedStep {
+        if let NamedStep::Real { name } = &self {
+            let deadline = Instant::now() + *TIMEOUT.lock();
+            let mut flags = STEP_FLAGS.lock();
+            while !predecessors
+                .iter()
+                .all(|name| *flags.get(name).unwrap_or(&false))
+            {
+                if ABORT.load(Ordering::SeqCst) {
+                    panic!("{}", named_report("aborted", name, predecessors, &flags));
+                }
+                if NAMED_CONDVAR.wait_until(&mut flags, deadline).timed_out()
+                    && !predecessors
+                        .iter()
+                        .all(|name| *flags.get(name).unwrap_or(&false))
+                {
+                    let report = named_report("timed out", name, predecessors, &flags);
+                    // Unwind the whole run, waking both named and numeric waiters.
+                    ABORT.store(true, Ordering::SeqCst);
+                    NAMED_CONDVAR.notify_all();
+                    unpark_all();
+                    panic!("{}", report);
+                }
+            }
+        }
+        self
+    }
+}
+
+/// Build a diagnostic for a named step that could not proceed, listing the predecessors that are
+/// still outstanding.
+fn named_report(
+    what: &str,
+    name: &str,
+    predecessors: &[&'static str],
+    flags: &HashMap<&'static str, bool>,
+) -> String {
+    let pending: Vec<&'static str> = predecessors
+        .iter()
+        .copied()
+        .filter(|predecessor| !*flags.get(predecessor).unwrap_or(&false))
+        .collect();
+    format!(
+        "Hiatus {}: named step {:?} is still waiting for predecessors {:?}",
+        what, name, pending,
+    )
+}
+
+impl Drop for NamedStep {
+    /// Mark this step complete and wake up anything waiting on it.
+    fn drop(&mut self) {
+        if let NamedStep::Real { name } = self {
+            STEP_FLAGS.lock().insert(name, true);
+            NAMED_CONDVAR.notify_all();
         }
     }
 }